@@ -0,0 +1,129 @@
+//! YAML configuration describing which peripheral to drive and how to
+//! translate gamepad input into command bytes.
+//!
+//! This lets carbleurator retarget a different BLE car without
+//! recompiling: point it at a new config file instead of editing code.
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::control::ControlChannel;
+use crate::discovery::Filter;
+
+/// Identifies the car to connect to, by one of several criteria.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeripheralTarget {
+    NamePrefix { prefix: String },
+    Address { address: String },
+    Service { uuid: String },
+}
+
+/// A single gilrs input bound to a control channel or a literal command.
+///
+/// Each entry is tagged by `type`, so the mapping table can mix axis and
+/// button entries and have each one instantiated as the right variant.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MappingEntry {
+    Axis { axis: String, channel: ControlChannel },
+    Button { button: String, command: Vec<u8> },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppConfig {
+    pub peripheral: PeripheralTarget,
+    pub command_characteristic: String,
+    #[serde(default)]
+    pub mappings: Vec<MappingEntry>,
+}
+
+impl AppConfig {
+    /// Loads and parses an `AppConfig` from a YAML file on disk.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<AppConfig> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open config file {}", path.display()))?;
+        serde_yaml::from_reader(file)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Converts the configured target into a `discovery::Filter` that can
+    /// be matched against scan results.
+    pub fn peripheral_filter(&self) -> Result<Filter> {
+        Ok(match &self.peripheral {
+            PeripheralTarget::NamePrefix { prefix } => Filter::NamePrefix(prefix.clone()),
+            PeripheralTarget::Address { address } => Filter::Address(address.clone()),
+            PeripheralTarget::Service { uuid } => Filter::Service(
+                uuid.parse()
+                    .with_context(|| format!("invalid service UUID {}", uuid))?,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_representative_config() {
+        let yaml = r#"
+peripheral:
+  type: name_prefix
+  prefix: "RC-Car"
+command_characteristic: "0000ffe1-0000-1000-8000-00805f9b34fb"
+mappings:
+  - type: axis
+    axis: left_stick_x
+    channel: steering
+  - type: button
+    button: south
+    command: [1, 0, 0]
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert!(matches!(
+            config.peripheral,
+            PeripheralTarget::NamePrefix { ref prefix } if prefix == "RC-Car"
+        ));
+        assert_eq!(
+            config.command_characteristic,
+            "0000ffe1-0000-1000-8000-00805f9b34fb"
+        );
+        assert_eq!(config.mappings.len(), 2);
+        assert!(matches!(
+            &config.mappings[0],
+            MappingEntry::Axis { axis, channel: ControlChannel::Steering } if axis == "left_stick_x"
+        ));
+        assert!(matches!(
+            &config.mappings[1],
+            MappingEntry::Button { button, command } if button == "south" && command == &[1, 0, 0]
+        ));
+    }
+
+    #[test]
+    fn mappings_default_to_empty_when_omitted() {
+        let yaml = r#"
+peripheral:
+  type: address
+  address: "AA:BB:CC:DD:EE:FF"
+command_characteristic: "0000ffe1-0000-1000-8000-00805f9b34fb"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.mappings.is_empty());
+    }
+
+    #[test]
+    fn unknown_peripheral_type_tag_fails_to_parse() {
+        let yaml = r#"
+peripheral:
+  type: not_a_real_type
+command_characteristic: "0000ffe1-0000-1000-8000-00805f9b34fb"
+"#;
+        assert!(serde_yaml::from_str::<AppConfig>(yaml).is_err());
+    }
+}
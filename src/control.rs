@@ -0,0 +1,366 @@
+//! Translates gamepad input into BLE commands for the car.
+//!
+//! This module owns the connection to the chosen `Peripheral` once it has
+//! been discovered, and keeps a normalized `ControlState` up to date as
+//! gilrs events arrive. The state is serialized into a small fixed frame
+//! and written to the car's command characteristic.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use btleplug::api::{CharPropFlags, Characteristic, Peripheral, ValueNotification};
+use failure::Fail;
+use gilrs::{Axis, Button, EventType};
+use serde::Deserialize;
+
+use crate::config::MappingEntry;
+use crate::error;
+use crate::signaling::{update_signal_failure, update_signal_low_battery};
+
+/// Which part of `ControlState` an axis mapping entry drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlChannel {
+    Steering,
+    Throttle,
+}
+
+/// Normalized steering/throttle/button state, independent of any one
+/// gamepad's raw axis ranges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ControlState {
+    pub steering: i8,
+    pub throttle: i8,
+    pub buttons: u8,
+}
+
+const BUTTON_SOUTH: u8 = 1 << 0;
+const BUTTON_EAST: u8 = 1 << 1;
+const BUTTON_NORTH: u8 = 1 << 2;
+const BUTTON_WEST: u8 = 1 << 3;
+
+impl ControlState {
+    /// Folds a single gilrs event into the current state. Unrecognized
+    /// axes/buttons are ignored.
+    pub fn apply_event(&mut self, event: &EventType) {
+        match *event {
+            EventType::AxisChanged(Axis::LeftStickX, value, _) => {
+                self.steering = scale_axis(value);
+            }
+            EventType::AxisChanged(Axis::RightZ, value, _) => {
+                self.throttle = scale_axis(value);
+            }
+            EventType::ButtonPressed(button, _) => self.set_button(button, true),
+            EventType::ButtonReleased(button, _) => self.set_button(button, false),
+            _ => {}
+        }
+    }
+
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        let mask = match button {
+            Button::South => BUTTON_SOUTH,
+            Button::East => BUTTON_EAST,
+            Button::North => BUTTON_NORTH,
+            Button::West => BUTTON_WEST,
+            _ => return,
+        };
+        if pressed {
+            self.buttons |= mask;
+        } else {
+            self.buttons &= !mask;
+        }
+    }
+
+    /// Serializes this state into the fixed 3-byte command frame
+    /// understood by the car's command characteristic.
+    pub fn to_frame(self) -> [u8; 3] {
+        [self.steering as u8, self.throttle as u8, self.buttons]
+    }
+
+    /// Folds an event into this state using a config-provided mapping
+    /// table instead of the hardcoded axis/button assignment. Axis
+    /// entries update the channel they're bound to; a matching button
+    /// entry returns its literal command bytes to be sent as a one-off,
+    /// since a discrete command doesn't live on the continuous frame.
+    pub fn apply_mapped_event(
+        &mut self,
+        event: &EventType,
+        mappings: &[MappingEntry],
+    ) -> Option<Vec<u8>> {
+        if mappings.is_empty() {
+            self.apply_event(event);
+            return None;
+        }
+        for entry in mappings {
+            match entry {
+                MappingEntry::Axis { axis, channel } => {
+                    if let EventType::AxisChanged(gilrs_axis, value, _) = *event {
+                        if parse_axis(axis) == Some(gilrs_axis) {
+                            let scaled = scale_axis(value);
+                            match channel {
+                                ControlChannel::Steering => self.steering = scaled,
+                                ControlChannel::Throttle => self.throttle = scaled,
+                            }
+                        }
+                    }
+                }
+                MappingEntry::Button { button, command } => {
+                    if let EventType::ButtonPressed(gilrs_button, _) = *event {
+                        if parse_button(button) == Some(gilrs_button) {
+                            return Some(command.clone());
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses a config axis name (e.g. `"left_stick_x"`) into a gilrs `Axis`.
+fn parse_axis(name: &str) -> Option<Axis> {
+    match name {
+        "left_stick_x" => Some(Axis::LeftStickX),
+        "left_stick_y" => Some(Axis::LeftStickY),
+        "right_stick_x" => Some(Axis::RightStickX),
+        "right_stick_y" => Some(Axis::RightStickY),
+        "left_z" => Some(Axis::LeftZ),
+        "right_z" => Some(Axis::RightZ),
+        _ => None,
+    }
+}
+
+/// Parses a config button name (e.g. `"south"`) into a gilrs `Button`.
+fn parse_button(name: &str) -> Option<Button> {
+    match name {
+        "south" => Some(Button::South),
+        "east" => Some(Button::East),
+        "north" => Some(Button::North),
+        "west" => Some(Button::West),
+        "dpad_up" => Some(Button::DPadUp),
+        "dpad_down" => Some(Button::DPadDown),
+        "dpad_left" => Some(Button::DPadLeft),
+        "dpad_right" => Some(Button::DPadRight),
+        _ => None,
+    }
+}
+
+/// Scales a gilrs axis value in `[-1.0, 1.0]` to the `i8` command range.
+fn scale_axis(value: f32) -> i8 {
+    (value.clamp(-1.0, 1.0) * i8::MAX as f32) as i8
+}
+
+#[cfg(test)]
+mod control_state_tests {
+    use super::*;
+
+    #[test]
+    fn scale_axis_clamps_before_scaling() {
+        assert_eq!(scale_axis(0.0), 0);
+        assert_eq!(scale_axis(1.0), i8::MAX);
+        assert_eq!(scale_axis(-1.0), -i8::MAX);
+        assert_eq!(scale_axis(2.0), i8::MAX);
+        assert_eq!(scale_axis(-2.0), -i8::MAX);
+    }
+
+    #[test]
+    fn set_button_toggles_its_own_bit_without_disturbing_others() {
+        let mut state = ControlState::default();
+        state.set_button(Button::South, true);
+        state.set_button(Button::West, true);
+        assert_eq!(state.buttons, BUTTON_SOUTH | BUTTON_WEST);
+
+        state.set_button(Button::South, false);
+        assert_eq!(state.buttons, BUTTON_WEST);
+    }
+
+    #[test]
+    fn set_button_ignores_buttons_with_no_mapped_bit() {
+        let mut state = ControlState::default();
+        state.set_button(Button::Select, true);
+        assert_eq!(state.buttons, 0);
+    }
+
+    #[test]
+    fn to_frame_packs_steering_throttle_and_buttons_in_order() {
+        let state = ControlState {
+            steering: -10,
+            throttle: 20,
+            buttons: BUTTON_EAST,
+        };
+        assert_eq!(state.to_frame(), [(-10i8) as u8, 20u8, BUTTON_EAST]);
+    }
+}
+
+/// Connects to `peripheral`, discovers its characteristics, and returns
+/// the one matching `command_characteristic_uuid` from the config.
+pub fn connect_and_prepare<P: Peripheral>(
+    peripheral: &P,
+    command_characteristic_uuid: &str,
+) -> Result<Characteristic> {
+    peripheral.connect().map_err(|e| {
+        error::connection_failed(e.compat(), peripheral.properties().address.to_string())
+    })?;
+
+    let characteristics = peripheral
+        .discover_characteristics()
+        .map_err(|e| e.compat())
+        .with_context(|| "Failed to discover characteristics".to_string())?;
+
+    let uuid = command_characteristic_uuid
+        .parse()
+        .with_context(|| format!("invalid command characteristic UUID {}", command_characteristic_uuid))?;
+
+    characteristics
+        .into_iter()
+        .find(|c| {
+            c.uuid == uuid
+                && (c.properties.contains(CharPropFlags::WRITE)
+                    || c.properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE))
+        })
+        .ok_or_else(|| error::characteristic_not_found(command_characteristic_uuid))
+}
+
+/// Battery level, as a percentage, at or below which we signal low battery.
+const LOW_BATTERY_THRESHOLD: u8 = 15;
+
+/// Telemetry reported by the car over its notify characteristic: battery
+/// level and a heartbeat so we can tell the car is still alive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Telemetry {
+    pub battery_percent: u8,
+    pub heartbeat: bool,
+}
+
+impl Telemetry {
+    fn from_frame(frame: &[u8]) -> Option<Telemetry> {
+        let (&battery_percent, &heartbeat) = (frame.first()?, frame.get(1)?);
+        Some(Telemetry {
+            battery_percent,
+            heartbeat: heartbeat != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod telemetry_tests {
+    use super::*;
+
+    #[test]
+    fn from_frame_rejects_frames_shorter_than_two_bytes() {
+        assert_eq!(Telemetry::from_frame(&[]), None);
+        assert_eq!(Telemetry::from_frame(&[42]), None);
+    }
+
+    #[test]
+    fn from_frame_parses_battery_and_heartbeat() {
+        assert_eq!(
+            Telemetry::from_frame(&[80, 1, 0xff]),
+            Some(Telemetry {
+                battery_percent: 80,
+                heartbeat: true,
+            })
+        );
+        assert_eq!(
+            Telemetry::from_frame(&[5, 0]),
+            Some(Telemetry {
+                battery_percent: 5,
+                heartbeat: false,
+            })
+        );
+    }
+}
+
+/// Finds a notify-capable characteristic on `peripheral`, if it has one.
+/// Telemetry is optional: not every car advertises it.
+pub fn find_telemetry_characteristic<P: Peripheral>(
+    peripheral: &P,
+) -> Result<Option<Characteristic>> {
+    let characteristics = peripheral
+        .discover_characteristics()
+        .map_err(|e| e.compat())
+        .with_context(|| "Failed to discover characteristics".to_string())?;
+
+    Ok(characteristics
+        .into_iter()
+        .find(|c| c.properties.contains(CharPropFlags::NOTIFY)))
+}
+
+/// Tracks the last time a telemetry frame arrived, so a stalled heartbeat
+/// can be detected from the transport thread's tick. `timed_out` records
+/// whether we've already signaled failure for the current stall, so
+/// `check_heartbeat_timeout` only calls `update_signal_failure` once per
+/// stall rather than on every tick until a fresh frame arrives.
+#[derive(Debug)]
+pub struct HeartbeatMonitor {
+    last_seen: Mutex<Instant>,
+    timed_out: AtomicBool,
+}
+
+impl Default for HeartbeatMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeartbeatMonitor {
+    pub fn new() -> Self {
+        Self {
+            last_seen: Mutex::new(Instant::now()),
+            timed_out: AtomicBool::new(false),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+        self.timed_out.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Subscribes to `characteristic` and routes parsed telemetry frames into
+/// `signaling`. Each frame refreshes `heartbeat`; a low battery reading
+/// signals once per dip below `LOW_BATTERY_THRESHOLD`, not on every frame
+/// the car stays low.
+pub fn subscribe_telemetry<P: Peripheral>(
+    peripheral: &P,
+    characteristic: &Characteristic,
+    heartbeat: Arc<HeartbeatMonitor>,
+) -> Result<()> {
+    peripheral
+        .subscribe(characteristic)
+        .map_err(|e| e.compat())
+        .with_context(|| "Failed to subscribe to telemetry characteristic".to_string())?;
+
+    let low_battery_signaled = AtomicBool::new(false);
+    peripheral.on_notification(Box::new(move |notification: ValueNotification| {
+        if let Some(telemetry) = Telemetry::from_frame(&notification.value) {
+            heartbeat.touch();
+            if telemetry.battery_percent <= LOW_BATTERY_THRESHOLD {
+                if !low_battery_signaled.swap(true, Ordering::Relaxed) {
+                    update_signal_low_battery();
+                }
+            } else {
+                low_battery_signaled.store(false, Ordering::Relaxed);
+            }
+        }
+    }));
+
+    Ok(())
+}
+
+/// Returns `true` if more than `timeout` has elapsed since the last
+/// heartbeat frame, signaling failure once on the transition into the
+/// stalled state rather than on every call while it remains stalled.
+pub fn check_heartbeat_timeout(heartbeat: &HeartbeatMonitor, timeout: Duration) -> bool {
+    let elapsed = heartbeat.last_seen.lock().unwrap().elapsed();
+    if elapsed > timeout {
+        if !heartbeat.timed_out.swap(true, Ordering::Relaxed) {
+            update_signal_failure();
+        }
+        true
+    } else {
+        false
+    }
+}
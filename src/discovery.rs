@@ -0,0 +1,118 @@
+//! Picking a single peripheral out of whatever the scan turns up.
+//!
+//! `find_peripheral` polls `Central::peripherals()` for up to a timeout,
+//! matching each candidate against a `Filter`. Match criteria (name
+//! prefix, address, advertised service) are modeled as data rather than
+//! hardcoded into the scan loop, so the config can pick whichever one
+//! identifies the car without touching this code.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use btleplug::api::{Central, Peripheral, UUID};
+
+use crate::error::CarbleuratorError;
+
+/// A single criterion for matching a discovered peripheral.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    NamePrefix(String),
+    Address(String),
+    Service(UUID),
+}
+
+impl Filter {
+    fn matches<P: Peripheral>(&self, peripheral: &P) -> bool {
+        let props = peripheral.properties();
+        self.matches_properties(props.local_name.as_deref(), &props.address.to_string(), &props.uuids)
+    }
+
+    /// The matching logic itself, pulled out of `matches` so it can be
+    /// exercised with plain values instead of a live `Peripheral`.
+    fn matches_properties(&self, local_name: Option<&str>, address: &str, uuids: &[UUID]) -> bool {
+        match self {
+            Filter::NamePrefix(prefix) => local_name.unwrap_or_default().starts_with(prefix.as_str()),
+            Filter::Address(expected) => address == *expected,
+            Filter::Service(uuid) => uuids.iter().any(|u| u == uuid),
+        }
+    }
+}
+
+/// Polls `central.peripherals()` until one matches `filter` or `timeout`
+/// elapses, whichever comes first. If the deadline passes without the
+/// scan ever reporting a single peripheral, that's a `ScanTimeout`
+/// (nothing to filter); if peripherals were seen but none matched, that's
+/// `NoMatchingPeripheral`.
+pub fn find_peripheral<C, P>(central: &C, filter: &Filter, timeout: Duration) -> Result<P>
+where
+    C: Central<P>,
+    P: Peripheral,
+{
+    let deadline = Instant::now() + timeout;
+    let mut saw_any_peripheral = false;
+    loop {
+        let peripherals = central.peripherals();
+        saw_any_peripheral |= !peripherals.is_empty();
+        if let Some(peripheral) = peripherals.into_iter().find(|p| filter.matches(p)) {
+            return Ok(peripheral);
+        }
+        if Instant::now() >= deadline {
+            return Err(timeout_error(saw_any_peripheral).into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Which error a deadline expiring without a match should produce:
+/// `NoMatchingPeripheral` if the scan reported peripherals that just
+/// didn't match, `ScanTimeout` if it never reported any at all.
+fn timeout_error(saw_any_peripheral: bool) -> CarbleuratorError {
+    if saw_any_peripheral {
+        CarbleuratorError::NoMatchingPeripheral
+    } else {
+        CarbleuratorError::ScanTimeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_prefix_matches_by_prefix_only() {
+        let filter = Filter::NamePrefix("RC-Car".to_string());
+        assert!(filter.matches_properties(Some("RC-Car-42"), "00:00:00:00:00:00", &[]));
+        assert!(!filter.matches_properties(Some("Other"), "00:00:00:00:00:00", &[]));
+        assert!(!filter.matches_properties(None, "00:00:00:00:00:00", &[]));
+    }
+
+    #[test]
+    fn address_matches_exact_string_only() {
+        let filter = Filter::Address("AA:BB:CC:DD:EE:FF".to_string());
+        assert!(filter.matches_properties(None, "AA:BB:CC:DD:EE:FF", &[]));
+        assert!(!filter.matches_properties(None, "11:22:33:44:55:66", &[]));
+    }
+
+    #[test]
+    fn service_matches_when_uuid_is_advertised() {
+        let target: UUID = "0000180d-0000-1000-8000-00805f9b34fb".parse().unwrap();
+        let other: UUID = "0000180a-0000-1000-8000-00805f9b34fb".parse().unwrap();
+        let filter = Filter::Service(target);
+        assert!(filter.matches_properties(None, "", &[target]));
+        assert!(!filter.matches_properties(None, "", &[other]));
+        assert!(!filter.matches_properties(None, "", &[]));
+    }
+
+    #[test]
+    fn timeout_error_is_no_matching_peripheral_once_any_were_seen() {
+        assert!(matches!(
+            timeout_error(true),
+            CarbleuratorError::NoMatchingPeripheral
+        ));
+    }
+
+    #[test]
+    fn timeout_error_is_scan_timeout_when_none_were_ever_seen() {
+        assert!(matches!(timeout_error(false), CarbleuratorError::ScanTimeout));
+    }
+}
@@ -0,0 +1,66 @@
+//! Carbleurator's error enum and the small conversion layer that maps
+//! lower-level btleplug/gilrs failures into typed variants, so callers can
+//! match on failure kind to decide whether to retry, reconnect, or abort.
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CarbleuratorError {
+    #[error("USB not supported")]
+    UsbNotSupportedError,
+    #[error("USB device initialization error")]
+    UsbDeviceInitializationError,
+    #[error("USB initialization error")]
+    UsbInitializationError(Box<dyn std::error::Error + Send + Sync>),
+    #[error("No USB gamepads found")]
+    MissingGamepad,
+    #[error("No BLE adapters found")]
+    MissingBleAdapter,
+    #[error("No peripheral matched the configured filter")]
+    NoMatchingPeripheral,
+    #[error("Failed to connect to peripheral at {address}")]
+    ConnectionFailed { address: String },
+    #[error("Characteristic {uuid} not found on peripheral")]
+    CharacteristicNotFound { uuid: String },
+    #[error("Failed to write command to peripheral")]
+    CommandWriteFailed,
+    #[error("Timed out scanning for BLE peripherals")]
+    ScanTimeout,
+}
+
+impl From<gilrs::Error> for CarbleuratorError {
+    fn from(err: gilrs::Error) -> Self {
+        match err {
+            gilrs::Error::NotImplemented(_) => Self::UsbNotSupportedError,
+            gilrs::Error::InvalidAxisToBtn => Self::UsbDeviceInitializationError,
+            gilrs::Error::Other(e) => Self::UsbInitializationError(e),
+        }
+    }
+}
+
+/// Wraps a failed connection attempt as a `ConnectionFailed`, keeping the
+/// underlying btleplug error as context so the original failure text
+/// isn't lost.
+pub fn connection_failed(
+    err: impl std::fmt::Display,
+    address: impl Into<String>,
+) -> anyhow::Error {
+    anyhow::Error::new(CarbleuratorError::ConnectionFailed {
+        address: address.into(),
+    })
+    .context(err.to_string())
+}
+
+/// Wraps a missing characteristic lookup as a `CharacteristicNotFound`.
+pub fn characteristic_not_found(uuid: impl std::fmt::Display) -> anyhow::Error {
+    CarbleuratorError::CharacteristicNotFound {
+        uuid: uuid.to_string(),
+    }
+    .into()
+}
+
+/// Wraps a failed command write as a `CommandWriteFailed`, keeping the
+/// underlying btleplug error as context.
+pub fn command_write_failed(err: impl std::fmt::Display) -> anyhow::Error {
+    anyhow::Error::new(CarbleuratorError::CommandWriteFailed).context(err.to_string())
+}
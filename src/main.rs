@@ -1,12 +1,39 @@
+use std::sync::{Arc, Barrier, Mutex};
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
-use thiserror::Error;
 // Bring in required traits
 use anyhow::Context;
 use btleplug::api::{Central, Peripheral};
 use failure::Fail;
 
+mod config;
+mod control;
+mod discovery;
+mod error;
 mod signaling;
+mod transport;
+use crate::config::AppConfig;
+use crate::control::ControlState;
+use crate::error::CarbleuratorError;
 use crate::signaling::{update_signal_failure, update_signal_progress, update_signal_success};
+use crate::transport::{BleTransport, Transport};
+
+const CONFIG_PATH: &str = "carbleurator.yaml";
+const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+const TRANSPORT_TICK: Duration = Duration::from_millis(50);
+const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(4);
+/// How long `send_with_reconnect` keeps retrying a single send before
+/// giving up and returning an error for that tick. A car that's genuinely
+/// gone (powered off, out of range) shouldn't wedge the transport thread
+/// forever; the next tick will start a fresh retry window.
+const RECONNECT_GIVE_UP_AFTER: Duration = Duration::from_secs(60);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many discrete button commands can queue up while the transport
+/// thread is busy reconnecting. Once full, the gamepad thread drops new
+/// commands rather than blocking or growing the backlog further.
+const COMMAND_QUEUE_CAPACITY: usize = 8;
 
 #[cfg(target_os = "linux")]
 use btleplug::bluez::{adapter::ConnectedAdapter as BleAdapter, manager::Manager as BleManager};
@@ -15,30 +42,6 @@ use btleplug::corebluetooth::{adapter::Adapter as BleAdapter, manager::Manager a
 #[cfg(target_os = "windows")]
 use btleplug::winrtble::{adapter::Adapter as BleAdapter, manager::Manager as BleManager};
 
-#[derive(Error, Debug)]
-pub enum CarbleuratorError {
-    #[error("USB not supported")]
-    UsbNotSupportedError,
-    #[error("USB device initialization error")]
-    UsbDeviceInitializationError,
-    #[error("USB initialization error")]
-    UsbInitializationError(Box<dyn std::error::Error + Send + Sync>),
-    #[error("No USB gamepads found")]
-    MissingGamepad,
-    #[error("No BLE adapters found")]
-    MissingBleAdapter,
-}
-
-impl From<gilrs::Error> for CarbleuratorError {
-    fn from(err: gilrs::Error) -> Self {
-        match err {
-            gilrs::Error::NotImplemented(_) => Self::UsbNotSupportedError,
-            gilrs::Error::InvalidAxisToBtn => Self::UsbDeviceInitializationError,
-            gilrs::Error::Other(e) => Self::UsbInitializationError(e),
-        }
-    }
-}
-
 pub(crate) fn init_gamepads() -> Result<gilrs::Gilrs> {
     let gilrs = gilrs::Gilrs::new().map_err(CarbleuratorError::from)?;
     if gilrs.gamepads().count() == 0 {
@@ -67,6 +70,7 @@ fn get_central(manager: &BleManager) -> Result<BleAdapter> {
 
 fn main() -> Result<()> {
     update_signal_progress();
+    let config = AppConfig::load(CONFIG_PATH)?;
     // Init gamepads
     let mut gilrs = init_gamepads()?;
     for (_id, gamepad) in gilrs.gamepads() {
@@ -91,20 +95,266 @@ fn main() -> Result<()> {
     std::thread::sleep(std::time::Duration::from_secs(2));
     update_signal_progress();
 
-    for peripheral in central.peripherals() {
-        println!(
-            "{} ({})",
-            peripheral.properties().local_name.unwrap_or_default(),
-            peripheral.properties().address
-        );
-    }
+    // `find_peripheral` below does its own polling of `central.peripherals()`
+    // against the configured filter; there's no separate listing scan here
+    // to duplicate it.
+    let filter = config.peripheral_filter()?;
+    let peripheral = discovery::find_peripheral(&central, &filter, SCAN_TIMEOUT)?;
+    let telemetry_peripheral = peripheral.clone();
+
+    let (event_tx, event_rx) = std::sync::mpsc::channel();
+    let mut car = BleTransport::new(peripheral, config.command_characteristic.clone(), event_tx);
+    car.connect()?;
 
     update_signal_success();
-    // Start event loop
+
+    // Telemetry is optional: not every car advertises a notify
+    // characteristic. When present, a stalled heartbeat or a low battery
+    // report feeds back into the same signaling the rest of the program
+    // uses for connection state.
+    let heartbeat = Arc::new(control::HeartbeatMonitor::new());
+    let has_telemetry = match control::find_telemetry_characteristic(&telemetry_peripheral) {
+        Ok(Some(telemetry_char)) => control::subscribe_telemetry(
+            &telemetry_peripheral,
+            &telemetry_char,
+            Arc::clone(&heartbeat),
+        )
+        .map(|()| true)
+        .unwrap_or_else(|err| {
+            eprintln!("telemetry subscription failed: {:#}", err);
+            false
+        }),
+        Ok(None) => false,
+        Err(err) => {
+            eprintln!("telemetry discovery failed: {:#}", err);
+            false
+        }
+    };
+    let telemetry = has_telemetry.then(|| (heartbeat, HEARTBEAT_TIMEOUT));
+
+    // Gamepad polling and BLE writes run on separate threads so a stalled
+    // write can't freeze input handling. A barrier holds both until the
+    // initial scan/connect above has already succeeded.
+    //
+    // The continuous control state (steering/throttle/held buttons) is
+    // shared directly rather than queued: the transport thread only ever
+    // wants the latest value, and queuing it would let a prolonged outage
+    // build up a backlog of stale state. Discrete one-off button commands
+    // go over a small bounded channel instead, so a reconnect storm can
+    // only ever leave a handful of them waiting, not an unbounded replay
+    // of every button press made during the outage.
+    let shared_state = Arc::new(Mutex::new(ControlState::default()));
+    let (command_tx, command_rx) = std::sync::mpsc::sync_channel(COMMAND_QUEUE_CAPACITY);
+
+    let barrier = Arc::new(Barrier::new(2));
+    let gamepad_barrier = Arc::clone(&barrier);
+    let mappings = config.mappings.clone();
+    let gamepad_state = Arc::clone(&shared_state);
+    let gamepad_handle = std::thread::spawn(move || {
+        gamepad_barrier.wait();
+        run_gamepad_loop(gilrs, mappings, gamepad_state, command_tx);
+    });
+
+    let transport_barrier = Arc::clone(&barrier);
+    let transport_handle = std::thread::spawn(move || {
+        transport_barrier.wait();
+        run_transport_loop(car, shared_state, command_rx, telemetry);
+    });
+
+    // Device lifecycle events are only logged here; connection-state
+    // signaling during reconnects is handled directly in the transport
+    // thread's reconnect routine below.
+    for event in event_rx {
+        println!("device event: {:?}", event);
+    }
+
+    gamepad_handle.join().expect("gamepad thread panicked");
+    transport_handle.join().expect("transport thread panicked");
+    Ok(())
+}
+
+/// Reads gilrs events, folding continuous axis/button state directly into
+/// the shared `state` and pushing any discrete button command onto
+/// `commands` for the transport thread to send. `commands` is bounded: if
+/// it's full (the transport thread is mid-reconnect), the new command is
+/// dropped and the ones already queued are kept, rather than growing an
+/// unbounded backlog of stale button presses to replay later.
+fn run_gamepad_loop(
+    mut gilrs: gilrs::Gilrs,
+    mappings: Vec<config::MappingEntry>,
+    state: Arc<Mutex<ControlState>>,
+    commands: std::sync::mpsc::SyncSender<Vec<u8>>,
+) {
     loop {
         while let Some(gilrs::Event { id, event, time }) = gilrs.next_event() {
             println!("{:?} New event from {}: {:?}", time, id, event);
+            let command = state.lock().unwrap().apply_mapped_event(&event, &mappings);
+            if let Some(command) = command {
+                match commands.try_send(command) {
+                    Ok(()) => {}
+                    Err(std::sync::mpsc::TrySendError::Full(_)) => {
+                        eprintln!("command queue full; dropping stale button command");
+                    }
+                    Err(std::sync::mpsc::TrySendError::Disconnected(_)) => return,
+                }
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
+
+/// Drains `commands` at a fixed rate and writes each to the car, then
+/// sends the latest shared control state. A failed write drives the car
+/// into a reconnect routine with exponential backoff.
+fn run_transport_loop<P: Peripheral + 'static>(
+    mut car: BleTransport<P>,
+    state: Arc<Mutex<ControlState>>,
+    commands: std::sync::mpsc::Receiver<Vec<u8>>,
+    telemetry: Option<(Arc<control::HeartbeatMonitor>, Duration)>,
+) {
+    loop {
+        match commands.recv_timeout(TRANSPORT_TICK) {
+            Ok(command) => send_command_with_reconnect(&mut car, &command),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+        while let Ok(command) = commands.try_recv() {
+            send_command_with_reconnect(&mut car, &command);
+        }
+
+        if let Some((heartbeat, timeout)) = &telemetry {
+            control::check_heartbeat_timeout(heartbeat, *timeout);
+        }
+
+        let frame = state.lock().unwrap().to_frame();
+        if let Err(err) = send_with_reconnect(&mut car, &frame, RECONNECT_GIVE_UP_AFTER) {
+            eprintln!("giving up on this tick: {:#}", err);
+        }
+    }
+}
+
+fn send_command_with_reconnect<T: Transport>(car: &mut T, command: &[u8]) {
+    if let Err(err) = send_with_reconnect(car, command, RECONNECT_GIVE_UP_AFTER) {
+        eprintln!("failed to send button command after reconnect attempts: {:#}", err);
+    }
+}
+
+/// Sends `frame`, and on failure repeatedly reconnects with exponential
+/// backoff (capped at `RECONNECT_MAX_BACKOFF`), logging each retry so a
+/// stalled link is visible on stderr instead of silent. Gives up and
+/// returns `Err` once `give_up_after` has elapsed without a successful
+/// send; the caller's next tick starts a fresh retry window.
+fn send_with_reconnect<T: Transport>(car: &mut T, frame: &[u8], give_up_after: Duration) -> Result<()> {
+    if car.send_command(frame).is_ok() {
+        return Ok(());
+    }
+
+    update_signal_failure();
+    let deadline = Instant::now() + give_up_after;
+    let mut backoff = RECONNECT_INITIAL_BACKOFF;
+    loop {
+        std::thread::sleep(backoff);
+        let _ = car.disconnect();
+        if car.connect().is_ok() {
+            update_signal_success();
+            if car.send_command(frame).is_ok() {
+                return Ok(());
+            }
+            update_signal_failure();
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow::anyhow!(
+                "gave up reconnecting after {:?}",
+                give_up_after
+            ));
+        }
+        eprintln!("still retrying to reconnect to the car...");
+        backoff = next_backoff(backoff, RECONNECT_MAX_BACKOFF);
+    }
+}
+
+/// Doubles `current`, capped at `max`. Pulled out of the retry loop so the
+/// arithmetic itself can be unit-tested without driving a real transport.
+fn next_backoff(current: Duration, max: Duration) -> Duration {
+    (current * 2).min(max)
+}
+
+#[cfg(test)]
+mod reconnect_tests {
+    use super::*;
+
+    #[test]
+    fn next_backoff_doubles_until_it_hits_the_cap() {
+        let cap = Duration::from_secs(4);
+        assert_eq!(next_backoff(Duration::from_millis(250), cap), Duration::from_millis(500));
+        assert_eq!(next_backoff(Duration::from_secs(3), cap), cap);
+        assert_eq!(next_backoff(Duration::from_secs(10), cap), cap);
+    }
+
+    /// A `Transport` whose sends fail a fixed number of times before
+    /// succeeding, so the reconnect path can be driven without a real
+    /// BLE stack.
+    struct FlakyTransport {
+        send_failures_remaining: usize,
+        connect_calls: usize,
+        send_calls: usize,
+    }
+
+    impl Transport for FlakyTransport {
+        fn connect(&mut self) -> Result<()> {
+            self.connect_calls += 1;
+            Ok(())
+        }
+
+        fn send_command(&mut self, _data: &[u8]) -> Result<()> {
+            self.send_calls += 1;
+            if self.send_failures_remaining > 0 {
+                self.send_failures_remaining -= 1;
+                Err(anyhow::anyhow!("simulated send failure"))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn disconnect(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A `Transport` whose sends never succeed, to exercise the give-up path.
+    struct AlwaysFailTransport;
+
+    impl Transport for AlwaysFailTransport {
+        fn connect(&mut self) -> Result<()> {
+            Ok(())
         }
-        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        fn send_command(&mut self, _data: &[u8]) -> Result<()> {
+            Err(anyhow::anyhow!("simulated send failure"))
+        }
+
+        fn disconnect(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_with_reconnect_recovers_after_a_transient_failure() {
+        let mut transport = FlakyTransport {
+            send_failures_remaining: 1,
+            connect_calls: 0,
+            send_calls: 0,
+        };
+        let result = send_with_reconnect(&mut transport, &[1, 2, 3], Duration::from_secs(5));
+        assert!(result.is_ok());
+        assert_eq!(transport.connect_calls, 1);
+        assert_eq!(transport.send_calls, 2);
+    }
+
+    #[test]
+    fn send_with_reconnect_gives_up_once_the_deadline_passes() {
+        let mut transport = AlwaysFailTransport;
+        let result = send_with_reconnect(&mut transport, &[1, 2, 3], Duration::from_millis(200));
+        assert!(result.is_err());
     }
 }
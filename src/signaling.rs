@@ -0,0 +1,21 @@
+//! Status signaling for carbleurator's startup and runtime state.
+//!
+//! These hooks are intentionally side-effect-light (stdout) so the crate
+//! doesn't pull in a board-specific LED/GPIO dependency; swap the bodies
+//! for real hardware signaling if/when a target board is wired up.
+
+pub fn update_signal_progress() {
+    println!("[signal] progress");
+}
+
+pub fn update_signal_success() {
+    println!("[signal] success");
+}
+
+pub fn update_signal_failure() {
+    println!("[signal] failure");
+}
+
+pub fn update_signal_low_battery() {
+    println!("[signal] low battery");
+}
@@ -0,0 +1,102 @@
+//! Decouples command delivery from discovery.
+//!
+//! `Transport` is the seam between the gamepad/translation logic and
+//! whatever link actually carries bytes to the car. `BleTransport` is the
+//! only implementation today, but a serial/HCI transport could be added
+//! later without touching `control` or `main`. A transport doesn't expose
+//! its raw peripheral/connection; it reports connect/disconnect/error as
+//! `DeviceEvent`s over a channel instead, so callers don't need to know
+//! what kind of link is underneath.
+
+use std::sync::mpsc::Sender;
+
+use anyhow::{Context, Result};
+use btleplug::api::{Characteristic, Peripheral};
+use failure::Fail;
+
+use crate::control;
+use crate::error;
+
+/// A lifecycle event reported by a transport as it connects, drops, or
+/// fails, so the rest of the program (including signaling) can react
+/// without reaching into the underlying connection.
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    Connected,
+    Disconnected,
+    Error(String),
+}
+
+/// Delivers command frames to a car, independent of the underlying link.
+pub trait Transport {
+    fn connect(&mut self) -> Result<()>;
+    fn send_command(&mut self, data: &[u8]) -> Result<()>;
+    fn disconnect(&mut self) -> Result<()>;
+}
+
+/// A `Transport` backed by a BLE peripheral and its command characteristic.
+pub struct BleTransport<P: Peripheral> {
+    peripheral: P,
+    command_characteristic_uuid: String,
+    command_characteristic: Option<Characteristic>,
+    events: Sender<DeviceEvent>,
+}
+
+impl<P: Peripheral> BleTransport<P> {
+    pub fn new(peripheral: P, command_characteristic_uuid: String, events: Sender<DeviceEvent>) -> Self {
+        Self {
+            peripheral,
+            command_characteristic_uuid,
+            command_characteristic: None,
+            events,
+        }
+    }
+
+    fn emit(&self, event: DeviceEvent) {
+        // The receiving end may have been dropped; there's no one left to
+        // tell, so ignore the send failure.
+        let _ = self.events.send(event);
+    }
+}
+
+impl<P: Peripheral> Transport for BleTransport<P> {
+    fn connect(&mut self) -> Result<()> {
+        match control::connect_and_prepare(&self.peripheral, &self.command_characteristic_uuid) {
+            Ok(characteristic) => {
+                self.command_characteristic = Some(characteristic);
+                self.emit(DeviceEvent::Connected);
+                Ok(())
+            }
+            Err(err) => {
+                self.emit(DeviceEvent::Error(err.to_string()));
+                Err(err)
+            }
+        }
+    }
+
+    fn send_command(&mut self, data: &[u8]) -> Result<()> {
+        let characteristic = self
+            .command_characteristic
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("transport is not connected"))?;
+        let result = self
+            .peripheral
+            .command(characteristic, data)
+            .map_err(|e| error::command_write_failed(e.compat()));
+        if let Err(err) = &result {
+            self.emit(DeviceEvent::Error(err.to_string()));
+        }
+        result
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        let result = self
+            .peripheral
+            .disconnect()
+            .map_err(|e| e.compat())
+            .with_context(|| "Failed to disconnect from peripheral".to_string());
+        self.command_characteristic = None;
+        self.emit(DeviceEvent::Disconnected);
+        result
+    }
+}